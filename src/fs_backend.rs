@@ -0,0 +1,222 @@
+use std::{path::Path, pin::Pin};
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use tokio::io::AsyncWriteExt;
+
+/// A chunk stream as produced by a reqwest response body.
+pub(crate) type BytesStream = Pin<Box<dyn Stream<Item = reqwest::Result<Bytes>> + Send>>;
+
+/// File I/O used by [`crate::export`], abstracted so the caller doesn't
+/// need to know which concrete backend is doing the reading/writing.
+#[async_trait::async_trait]
+pub(crate) trait FsBackend: Send + Sync {
+    async fn read_to_string(&self, path: &Path) -> Result<String>;
+
+    /// Writes `stream` to `tmp_path` and renames it to `final_path` once
+    /// complete, so a partial/failed write never leaves a corrupt file at
+    /// `final_path`. A partial `tmp_path` file is not removed.
+    async fn write_stream(
+        &self,
+        tmp_path: &Path,
+        final_path: &Path,
+        stream: BytesStream,
+    ) -> Result<()>;
+}
+
+pub(crate) struct TokioFsBackend;
+
+#[async_trait::async_trait]
+impl FsBackend for TokioFsBackend {
+    async fn read_to_string(&self, path: &Path) -> Result<String> {
+        Ok(tokio::fs::read_to_string(path).await?)
+    }
+
+    async fn write_stream(
+        &self,
+        tmp_path: &Path,
+        final_path: &Path,
+        mut stream: BytesStream,
+    ) -> Result<()> {
+        let mut out = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(tmp_path)
+            .await?;
+        while let Some(chunk) = stream.next().await {
+            out.write_all(&chunk?).await?;
+        }
+        out.flush().await?;
+        drop(out);
+        tokio::fs::rename(tmp_path, final_path).await?;
+        Ok(())
+    }
+}
+
+/// `io_uring`-backed `FsBackend`, opt-in via the `io-uring` feature and only
+/// ever selected on Linux.
+///
+/// `tokio_uring::fs` ops return `!Send` futures tied to their own
+/// single-threaded runtime, which is incompatible with the `#[tokio::main]`
+/// multi-threaded runtime `export` is spawned on via `JoinSet`. Rather than
+/// making `FsBackend` itself `?Send` (which would infect every caller), this
+/// runs a dedicated `tokio_uring` runtime on its own OS thread and bridges
+/// to it with a plain `Send` channel: the trait methods below just hand off
+/// a job and await the reply, so they stay ordinary `Send` futures from the
+/// caller's point of view.
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring {
+    use super::{BytesStream, FsBackend, Result};
+    use futures::StreamExt;
+    use std::path::{Path, PathBuf};
+    use tokio::sync::{mpsc, oneshot};
+
+    enum Job {
+        Read {
+            path: PathBuf,
+            reply: oneshot::Sender<Result<String>>,
+        },
+        Write {
+            tmp_path: PathBuf,
+            final_path: PathBuf,
+            bytes: Vec<u8>,
+            reply: oneshot::Sender<Result<()>>,
+        },
+    }
+
+    pub(crate) struct UringFsBackend {
+        jobs: mpsc::UnboundedSender<Job>,
+    }
+
+    impl UringFsBackend {
+        /// Spawns the dedicated `tokio_uring` runtime thread and returns a
+        /// handle that forwards read/write requests to it.
+        pub(crate) fn spawn() -> Self {
+            let (jobs, mut rx) = mpsc::unbounded_channel::<Job>();
+            std::thread::Builder::new()
+                .name("io-uring".to_string())
+                .spawn(move || {
+                    tokio_uring::start(async move {
+                        while let Some(job) = rx.recv().await {
+                            match job {
+                                Job::Read { path, reply } => {
+                                    let _ = reply.send(read_to_string(&path).await);
+                                }
+                                Job::Write {
+                                    tmp_path,
+                                    final_path,
+                                    bytes,
+                                    reply,
+                                } => {
+                                    let _ = reply.send(
+                                        write_and_rename(&tmp_path, &final_path, bytes).await,
+                                    );
+                                }
+                            }
+                        }
+                    });
+                })
+                .expect("failed to spawn io_uring runtime thread");
+            Self { jobs }
+        }
+
+        async fn request<T>(
+            &self,
+            job: impl FnOnce(oneshot::Sender<Result<T>>) -> Job,
+        ) -> Result<T> {
+            let (reply, recv) = oneshot::channel();
+            self.jobs
+                .send(job(reply))
+                .map_err(|_| anyhow::anyhow!("io_uring runtime thread is gone"))?;
+            recv.await
+                .map_err(|_| anyhow::anyhow!("io_uring runtime thread dropped the reply"))?
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl FsBackend for UringFsBackend {
+        async fn read_to_string(&self, path: &Path) -> Result<String> {
+            let path = path.to_path_buf();
+            self.request(|reply| Job::Read { path, reply }).await
+        }
+
+        async fn write_stream(
+            &self,
+            tmp_path: &Path,
+            final_path: &Path,
+            mut stream: BytesStream,
+        ) -> Result<()> {
+            // The job has to cross onto the `tokio_uring` thread as owned,
+            // `Send` data, so the chunks are assembled here first; the
+            // payoff of this backend is fewer syscalls per file on batch
+            // runs, not bounding a single file's peak memory.
+            let mut bytes = Vec::new();
+            while let Some(chunk) = stream.next().await {
+                bytes.extend_from_slice(&chunk?);
+            }
+            let tmp_path = tmp_path.to_path_buf();
+            let final_path = final_path.to_path_buf();
+            self.request(|reply| Job::Write {
+                tmp_path,
+                final_path,
+                bytes,
+                reply,
+            })
+            .await
+        }
+    }
+
+    async fn read_to_string(path: &Path) -> Result<String> {
+        let file = tokio_uring::fs::File::open(path).await?;
+        let mut contents = Vec::new();
+        let mut pos = 0u64;
+        loop {
+            let buf = vec![0u8; 64 * 1024];
+            let (res, buf) = file.read_at(buf, pos).await;
+            let n = res?;
+            if n == 0 {
+                break;
+            }
+            contents.extend_from_slice(&buf[..n]);
+            pos += n as u64;
+        }
+        file.close().await?;
+        Ok(String::from_utf8(contents)?)
+    }
+
+    async fn write_and_rename(tmp_path: &Path, final_path: &Path, bytes: Vec<u8>) -> Result<()> {
+        let file = tokio_uring::fs::File::create(tmp_path).await?;
+        let mut buf = bytes;
+        let mut pos = 0u64;
+        while !buf.is_empty() {
+            let (res, written) = file.write_at(buf, pos).await;
+            let n = res?;
+            if n == 0 {
+                anyhow::bail!("io_uring write to {} made no progress", tmp_path.display());
+            }
+            pos += n as u64;
+            buf = written[n..].to_vec();
+        }
+        file.sync_all().await?;
+        file.close().await?;
+        // `tokio::fs` isn't available inside the `tokio_uring` runtime, and
+        // a rename is cheap enough to do as a direct blocking syscall here.
+        std::fs::rename(tmp_path, final_path)?;
+        Ok(())
+    }
+}
+
+/// Picks the file I/O backend to use for the whole run. The `io_uring`
+/// backend is only compiled in behind the `io-uring` feature and only
+/// selected on Linux; every other target keeps using `tokio::fs`.
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub(crate) fn select_backend() -> std::sync::Arc<dyn FsBackend> {
+    std::sync::Arc::new(uring::UringFsBackend::spawn())
+}
+
+#[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+pub(crate) fn select_backend() -> std::sync::Arc<dyn FsBackend> {
+    std::sync::Arc::new(TokioFsBackend)
+}
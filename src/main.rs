@@ -1,18 +1,19 @@
+mod fs_backend;
+mod serve;
+
 use anyhow::{anyhow, Context, Result};
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use fs_backend::FsBackend;
 use log::{debug, info};
 use plantuml_encoding::encode_plantuml_deflate;
 use reqwest::{Client, Proxy};
-use std::{fmt::Display, path::PathBuf, str::FromStr};
+use serde::Deserialize;
+use std::{fmt::Display, path::PathBuf, str::FromStr, sync::Arc, time::Duration};
 
-use tokio::{
-    self, fs,
-    io::{AsyncReadExt, AsyncWriteExt},
-    task::JoinSet,
-};
+use tokio::{self, sync::Semaphore, task::JoinSet};
 
-#[derive(Debug, Copy, Clone)]
-enum Type {
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Type {
     Ascii,
     Png,
     Svg,
@@ -44,16 +45,190 @@ impl FromStr for Type {
     }
 }
 
+impl<'de> Deserialize<'de> for Type {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Type::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// HTTP method used to submit a diagram to the PlantUML server.
+#[derive(Debug, Copy, Clone)]
+enum Method {
+    /// Use GET, switching to POST once the encoded diagram would exceed
+    /// [`POST_URL_THRESHOLD`] characters.
+    Auto,
+    Get,
+    Post,
+}
+
+impl FromStr for Method {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "get" => Ok(Self::Get),
+            "post" => Ok(Self::Post),
+            _ => Err(format!("Unknown Method {s}")),
+        }
+    }
+}
+
+/// Practical URL-length limit (in encoded characters) many servers and
+/// proxies enforce; diagrams that would exceed it are sent as a POST body
+/// instead of being embedded in the GET path.
+const POST_URL_THRESHOLD: usize = 4096;
+
 #[derive(Parser, Debug)]
 struct Cli {
     /// Export Type
-    #[arg(short, long = "type", default_value = "svg")]
-    type_: Type,
+    #[arg(short, long = "type")]
+    type_: Option<Type>,
     /// Plantuml server url
-    #[arg(short, long, default_value = URL)]
-    url: String,
+    #[arg(short, long)]
+    url: Option<String>,
+    /// HTTP proxy to use for requests
+    #[arg(long)]
+    proxy: Option<String>,
+    /// Maximum number of concurrent export requests
+    #[arg(short, long)]
+    jobs: Option<usize>,
+    /// Maximum number of retries on a transient request failure
+    #[arg(long)]
+    max_retries: Option<u32>,
+    /// Path to a config file (defaults to the platform config dir)
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// HTTP method used to submit the diagram (auto, get, post)
+    #[arg(long, default_value = "auto")]
+    method: Method,
     /// Glob paths
     path: Vec<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Render diagrams on demand over HTTP instead of exporting to files
+    Serve {
+        /// Directory of .puml files to watch and serve renders for
+        dir: PathBuf,
+        /// Address to bind the HTTP server to
+        #[arg(short, long, default_value = "127.0.0.1:8080")]
+        bind: String,
+    },
+}
+
+/// Defaults for [`Cli`] options, loaded from a TOML config file.
+///
+/// CLI flags take priority over these values, which in turn take priority
+/// over the built-in defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Config {
+    #[serde(rename = "type")]
+    type_: Option<Type>,
+    url: Option<String>,
+    proxy: Option<String>,
+    jobs: Option<usize>,
+    max_retries: Option<u32>,
+}
+
+/// Resolved defaults for a run: CLI flags override the config file, which
+/// overrides these built-in defaults.
+struct Settings {
+    type_: Type,
+    url: String,
+    proxy: Option<String>,
+    jobs: usize,
+    max_retries: u32,
+}
+
+fn resolve_settings(cli: &Cli, config: Config) -> Settings {
+    Settings {
+        type_: cli.type_.or(config.type_).unwrap_or(Type::Svg),
+        url: cli
+            .url
+            .clone()
+            .or(config.url)
+            .unwrap_or_else(|| URL.to_string()),
+        proxy: cli.proxy.clone().or(config.proxy),
+        jobs: cli.jobs.or(config.jobs).unwrap_or_else(num_cpus::get),
+        max_retries: cli.max_retries.or(config.max_retries).unwrap_or(5),
+    }
+}
+
+fn default_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("puml-req").join("config.toml"))
+}
+
+fn load_config(path: Option<PathBuf>) -> Result<Config> {
+    let Some(path) = path.or_else(default_config_path).filter(|p| p.exists()) else {
+        return Ok(Config::default());
+    };
+    debug!("Loading config from {} ...", path.display());
+    let contents = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read config file {}", path.display()))?;
+    toml::from_str(&contents)
+        .with_context(|| format!("failed to parse config file {}", path.display()))
+}
+
+/// Base delay for the exponential backoff used by [`send_with_retry`].
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+/// Upper bound on the backoff delay. The exponent is separately capped at 16
+/// so a large `--max-retries` can't overflow `2u32.pow(attempt)` or the
+/// `Duration` multiply.
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(60);
+
+fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Whether a diagram of `encoded_len` (deflate-encoded, URL-safe characters)
+/// should be submitted via POST rather than embedded in the GET path.
+fn select_method(method: Method, encoded_len: usize) -> bool {
+    match method {
+        Method::Get => false,
+        Method::Post => true,
+        Method::Auto => encoded_len > POST_URL_THRESHOLD,
+    }
+}
+
+/// Sends `request`, retrying on a connect/timeout error or a retryable
+/// status until `max_retries` is exhausted. The returned response is always
+/// a success status; an error status (retryable and exhausted, or
+/// non-retryable like a 404) is turned into an `Err` rather than being
+/// handed back to the caller to write out as if it were the rendered image.
+async fn send_with_retry(
+    request: impl Fn() -> reqwest::RequestBuilder,
+    max_retries: u32,
+) -> Result<reqwest::Response> {
+    let mut attempt = 0;
+    loop {
+        let result = request().send().await;
+        let retryable = match &result {
+            Ok(res) => is_retryable_status(res.status()),
+            Err(e) => e.is_connect() || e.is_timeout(),
+        };
+        if !retryable || attempt >= max_retries {
+            let res = result.context("export request failed")?;
+            return res
+                .error_for_status()
+                .context("export request returned an error status");
+        }
+        let jitter = Duration::from_millis(rand::random::<u64>() % 250);
+        let delay = (RETRY_BASE_DELAY * 2u32.pow(attempt.min(16)) + jitter).min(RETRY_MAX_DELAY);
+        debug!(
+            "Export request failed (attempt {}/{max_retries}), retrying in {delay:?} ...",
+            attempt + 1
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
 }
 
 fn make_output_path(input: PathBuf, type_: Type) -> Result<PathBuf> {
@@ -67,35 +242,46 @@ fn make_output_path(input: PathBuf, type_: Type) -> Result<PathBuf> {
     Ok(out_path)
 }
 
-const URL: &str = "http://www.plantuml.com/plantuml";
+pub(crate) const URL: &str = "http://www.plantuml.com/plantuml";
 
-fn make_client() -> Result<Client> {
-    match std::env::var("http_proxy") {
-        Ok(proxy) => {
+pub(crate) fn make_client(proxy: Option<String>) -> Result<Client> {
+    match proxy.or_else(|| std::env::var("http_proxy").ok()) {
+        Some(proxy) => {
             debug!("Setting proxy to {proxy}");
             Ok(Client::builder().proxy(Proxy::http(proxy)?).build()?)
         }
-        _ => Ok(Client::new()),
+        None => Ok(Client::new()),
     }
 }
 
-async fn export(client: Client, path: PathBuf, url: String, type_: Type) -> Result<()> {
+async fn export(
+    client: Client,
+    path: PathBuf,
+    url: String,
+    type_: Type,
+    method: Method,
+    semaphore: Arc<Semaphore>,
+    max_retries: u32,
+    backend: Arc<dyn FsBackend>,
+) -> Result<()> {
+    let _permit = semaphore.acquire_owned().await?;
     info!("Processing {} ...", path.display());
-    let mut uml = fs::OpenOptions::new().read(true).open(&path).await?;
-    let mut uml_str = String::new();
-    uml.read_to_string(&mut uml_str).await?;
-    let encoded = encode_plantuml_deflate(uml_str).map_err(|e| anyhow!("{e:?}"))?;
-    let url = format!("{}/{}/{encoded}", url, type_);
-    let img = client.get(url).send().await?.bytes().await?;
+    let uml_str = backend.read_to_string(&path).await?;
+    let encoded = encode_plantuml_deflate(uml_str.clone()).map_err(|e| anyhow!("{e:?}"))?;
+    let use_post = select_method(method, encoded.len());
+    let response = if use_post {
+        let post_url = format!("{}/{}", url, type_);
+        send_with_retry(|| client.post(&post_url).body(uml_str.clone()), max_retries).await?
+    } else {
+        let get_url = format!("{}/{}/{encoded}", url, type_);
+        send_with_retry(|| client.get(&get_url), max_retries).await?
+    };
     let out_path = make_output_path(path, type_)?;
+    let tmp_path = out_path.with_extension(format!("{type_}.tmp"));
     info!("Writting to {} ...", out_path.display());
-    let mut out = fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(out_path)
+    backend
+        .write_stream(&tmp_path, &out_path, Box::pin(response.bytes_stream()))
         .await?;
-    out.write_all(&img).await?;
     Ok(())
 }
 
@@ -103,13 +289,135 @@ async fn export(client: Client, path: PathBuf, url: String, type_: Type) -> Resu
 async fn main() -> Result<()> {
     env_logger::init();
     let cli = Cli::parse();
-    let client = make_client()?;
+    let config = load_config(cli.config.clone())?;
+    let Settings {
+        type_,
+        url,
+        proxy,
+        jobs,
+        max_retries,
+    } = resolve_settings(&cli, config);
+
+    if let Some(Command::Serve { dir, bind }) = cli.command {
+        return serve::serve(dir, bind, url, proxy).await;
+    }
+
+    let client = make_client(proxy)?;
+    let semaphore = Arc::new(Semaphore::new(jobs));
+    let backend = fs_backend::select_backend();
     let mut set = JoinSet::new();
     for path in cli.path {
-        set.spawn(export(client.clone(), path, cli.url.clone(), cli.type_));
+        set.spawn(export(
+            client.clone(),
+            path,
+            url.clone(),
+            type_,
+            cli.method,
+            semaphore.clone(),
+            max_retries,
+            backend.clone(),
+        ));
     }
     while let Some(res) = set.join_next().await {
         res??;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_cli() -> Cli {
+        Cli {
+            type_: None,
+            url: None,
+            proxy: None,
+            jobs: None,
+            max_retries: None,
+            config: None,
+            method: Method::Auto,
+            path: Vec::new(),
+            command: None,
+        }
+    }
+
+    #[test]
+    fn resolve_settings_cli_overrides_config_overrides_default() {
+        let config = Config {
+            type_: Some(Type::Png),
+            url: Some("http://config.example".to_string()),
+            proxy: Some("http://config-proxy".to_string()),
+            jobs: Some(2),
+            max_retries: Some(3),
+        };
+
+        // Config wins over the built-in default when the CLI doesn't set it.
+        let settings = resolve_settings(&empty_cli(), config);
+        assert!(matches!(settings.type_, Type::Png));
+        assert_eq!(settings.url, "http://config.example");
+        assert_eq!(settings.jobs, 2);
+        assert_eq!(settings.max_retries, 3);
+
+        // A CLI flag wins over both the config file and the default.
+        let mut cli = empty_cli();
+        cli.url = Some("http://cli.example".to_string());
+        cli.jobs = Some(9);
+        let settings = resolve_settings(
+            &cli,
+            Config {
+                type_: None,
+                url: Some("http://config.example".to_string()),
+                proxy: None,
+                jobs: Some(2),
+                max_retries: None,
+            },
+        );
+        assert_eq!(settings.url, "http://cli.example");
+        assert_eq!(settings.jobs, 9);
+
+        // With neither CLI nor config set, fall back to the built-in default.
+        let settings = resolve_settings(&empty_cli(), Config::default());
+        assert!(matches!(settings.type_, Type::Svg));
+        assert_eq!(settings.url, URL);
+        assert_eq!(settings.max_retries, 5);
+    }
+
+    #[test]
+    fn is_retryable_status_flags_5xx_and_429() {
+        assert!(is_retryable_status(
+            reqwest::StatusCode::INTERNAL_SERVER_ERROR
+        ));
+        assert!(is_retryable_status(reqwest::StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_retryable_status(reqwest::StatusCode::NOT_FOUND));
+        assert!(!is_retryable_status(reqwest::StatusCode::OK));
+    }
+
+    #[test]
+    fn retry_delay_never_overflows_for_large_attempt_counts() {
+        for attempt in [0u32, 16, 32, u32::MAX] {
+            let delay = (RETRY_BASE_DELAY * 2u32.pow(attempt.min(16))).min(RETRY_MAX_DELAY);
+            assert!(delay <= RETRY_MAX_DELAY);
+        }
+    }
+
+    #[test]
+    fn method_from_str_accepts_known_values() {
+        assert!(matches!(Method::from_str("auto"), Ok(Method::Auto)));
+        assert!(matches!(Method::from_str("GET"), Ok(Method::Get)));
+        assert!(matches!(Method::from_str("post"), Ok(Method::Post)));
+        assert!(Method::from_str("patch").is_err());
+    }
+
+    #[test]
+    fn select_method_respects_explicit_choice() {
+        assert!(!select_method(Method::Get, usize::MAX));
+        assert!(select_method(Method::Post, 0));
+    }
+
+    #[test]
+    fn select_method_auto_switches_at_threshold() {
+        assert!(!select_method(Method::Auto, POST_URL_THRESHOLD));
+        assert!(select_method(Method::Auto, POST_URL_THRESHOLD + 1));
+    }
+}
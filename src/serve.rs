@@ -0,0 +1,161 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use log::{info, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use plantuml_encoding::encode_plantuml_deflate;
+use reqwest::Client;
+use tokio::fs;
+
+use crate::{make_client, Type, URL};
+
+/// Renders cached by (path relative to `dir`, output type); invalidated by
+/// the watcher whenever the underlying `.puml` file changes.
+type RenderCache = Mutex<HashMap<(PathBuf, Type), Vec<u8>>>;
+
+struct ServeState {
+    dir: PathBuf,
+    client: Client,
+    url: String,
+    cache: RenderCache,
+}
+
+/// Watches `dir` for changes, invalidating any cached render whose source
+/// file was touched so the next request for it re-renders from disk.
+fn watch_dir(dir: PathBuf, state: Arc<ServeState>) -> Result<RecommendedWatcher> {
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+        let event = match res {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("file watcher error: {e}");
+                return;
+            }
+        };
+        if !matches!(
+            event.kind,
+            EventKind::Modify(_) | EventKind::Create(_) | EventKind::Remove(_)
+        ) {
+            return;
+        }
+        let mut cache = state.cache.lock().unwrap();
+        for changed in &event.paths {
+            let Ok(rel) = changed.strip_prefix(&dir) else {
+                continue;
+            };
+            cache.retain(|(cached_path, _), _| cached_path != rel);
+        }
+    })?;
+    watcher.watch(&dir, RecursiveMode::Recursive)?;
+    Ok(watcher)
+}
+
+/// Turn `puml-req` into a long-running HTTP service that renders `.puml`
+/// diagrams on demand against the configured PlantUML backend.
+pub(crate) async fn serve(
+    dir: PathBuf,
+    bind: String,
+    url: String,
+    proxy: Option<String>,
+) -> Result<()> {
+    let state = Arc::new(ServeState {
+        dir: dir.clone(),
+        client: make_client(proxy)?,
+        url,
+        cache: Mutex::new(HashMap::new()),
+    });
+    // Kept alive for the lifetime of the server; dropping it stops the watch.
+    let _watcher = watch_dir(dir, state.clone())?;
+    let app = Router::new()
+        .route("/render/*path", get(render_file))
+        .route("/render", post(render_body))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(&bind).await?;
+    info!("Serving rendered diagrams on http://{bind} ...");
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+fn content_type(type_: Type) -> &'static str {
+    match type_ {
+        Type::Ascii => "text/plain; charset=utf-8",
+        Type::Png => "image/png",
+        Type::Svg => "image/svg+xml",
+    }
+}
+
+fn type_from_query(params: &HashMap<String, String>) -> Type {
+    params
+        .get("type")
+        .and_then(|t| t.parse().ok())
+        .unwrap_or(Type::Svg)
+}
+
+async fn render(state: &ServeState, uml: String, type_: Type) -> Result<Vec<u8>> {
+    let encoded = encode_plantuml_deflate(uml).map_err(|e| anyhow!("{e:?}"))?;
+    let url = format!("{}/{}/{encoded}", state.url, type_);
+    let bytes = state.client.get(url).send().await?.bytes().await?;
+    Ok(bytes.to_vec())
+}
+
+/// Reads `path` from `dir`, rejecting anything that escapes `dir` (via `..`
+/// components or a symlink) once canonicalized, so `/render/*path` can't be
+/// used to read arbitrary files off disk.
+async fn read_scoped_file(dir: &std::path::Path, path: &str) -> Result<String> {
+    let root = fs::canonicalize(dir).await?;
+    let requested = fs::canonicalize(dir.join(path)).await?;
+    if !requested.starts_with(&root) {
+        return Err(anyhow!("{} escapes the served directory", path));
+    }
+    Ok(fs::read_to_string(requested).await?)
+}
+
+/// Renders the `.puml` file at `path` (relative to the served directory).
+///
+/// The directory is watched for changes: a render is cached after the first
+/// request and reused until the watcher sees the source file modified,
+/// created or removed, at which point the cache entry is dropped and the
+/// next request re-reads and re-renders it from disk.
+async fn render_file(
+    State(state): State<Arc<ServeState>>,
+    Path(path): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, StatusCode> {
+    let type_ = type_from_query(&params);
+    let key = (PathBuf::from(&path), type_);
+    if let Some(cached) = state.cache.lock().unwrap().get(&key).cloned() {
+        return Ok(([(header::CONTENT_TYPE, content_type(type_))], cached).into_response());
+    }
+    let uml = read_scoped_file(&state.dir, &path)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    let img = render(&state, uml, type_)
+        .await
+        .map_err(|_| StatusCode::BAD_GATEWAY)?;
+    state.cache.lock().unwrap().insert(key, img.clone());
+    Ok(([(header::CONTENT_TYPE, content_type(type_))], img).into_response())
+}
+
+/// Renders diagram source posted directly in the request body.
+async fn render_body(
+    State(state): State<Arc<ServeState>>,
+    Query(params): Query<HashMap<String, String>>,
+    uml: String,
+) -> Result<Response, StatusCode> {
+    let type_ = type_from_query(&params);
+    let img = render(&state, uml, type_)
+        .await
+        .map_err(|_| StatusCode::BAD_GATEWAY)?;
+    Ok(([(header::CONTENT_TYPE, content_type(type_))], img).into_response())
+}